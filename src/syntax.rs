@@ -0,0 +1,518 @@
+//! Red-green syntax tree, rust-analyzer/rowan style.
+//!
+//! The `GreenNode`/`GreenToken` types form the "green tree": an immutable,
+//! relocatable, parent-less tree that only knows the *lengths* of its pieces
+//! and stores the exact source text for every token (including whitespace
+//! and comments). Structurally identical green subtrees are interned via
+//! [`NodeCache`] so e.g. every `nil` leaf in a term shares one allocation.
+//! Each node caches its own hash at construction time from its children's
+//! already-computed hashes, so interning a node costs O(children), not
+//! O(subtree) — building a deep chain stays linear overall.
+//!
+//! [`SyntaxNode`] is the "red" cursor on top: a position in a specific tree,
+//! computed lazily from a parent pointer plus the accumulated length of
+//! preceding siblings. Red nodes are cheap to create and throw away; the
+//! green tree underneath is the only thing that is actually stored.
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashSet;
+use std::hash::{Hash, Hasher};
+use std::ops::Range;
+use std::rc::Rc;
+
+/// Every token and composite that can appear in a CCS syntax tree.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum SyntaxKind {
+    // trivia
+    WHITESPACE,
+    COMMENT,
+    // tokens
+    IN,
+    OUT,
+    DOT,
+    PLUS,
+    PIPE,
+    BACKSLASH,
+    LBRACKET,
+    RBRACKET,
+    REC,
+    NIL,
+    NAME,
+    // composite nodes
+    PREFIX,
+    CHOICE,
+    COMPOSE,
+    RESTRICT,
+    RELABEL,
+    RECURSE,
+    ROOT,
+}
+
+impl SyntaxKind {
+    /// Whitespace and comments: carried by the tree so it round-trips, but
+    /// skipped by every accessor that looks for meaningful tokens.
+    pub fn is_trivia(self) -> bool {
+        matches!(self, SyntaxKind::WHITESPACE | SyntaxKind::COMMENT)
+    }
+}
+
+/// A half-open `[start, end)` byte range into the original source text.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct TextRange {
+    start: u32,
+    end: u32,
+}
+
+impl TextRange {
+    pub fn new(start: u32, end: u32) -> Self {
+        assert!(start <= end, "TextRange start {} after end {}", start, end);
+        TextRange { start, end }
+    }
+
+    pub fn start(&self) -> u32 {
+        self.start
+    }
+
+    pub fn end(&self) -> u32 {
+        self.end
+    }
+
+    pub fn len(&self) -> u32 {
+        self.end - self.start
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+}
+
+impl From<TextRange> for Range<usize> {
+    fn from(range: TextRange) -> Self {
+        range.start as usize..range.end as usize
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Hash)]
+struct GreenTokenData {
+    kind: SyntaxKind,
+    text: Box<str>,
+}
+
+/// A leaf of the green tree: a token kind plus its exact source text.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct GreenToken(Rc<GreenTokenData>);
+
+impl GreenToken {
+    pub fn new(kind: SyntaxKind, text: impl Into<Box<str>>) -> Self {
+        GreenToken(Rc::new(GreenTokenData {
+            kind,
+            text: text.into(),
+        }))
+    }
+
+    pub fn kind(&self) -> SyntaxKind {
+        self.0.kind
+    }
+
+    pub fn text(&self) -> &str {
+        &self.0.text
+    }
+
+    fn text_len(&self) -> u32 {
+        self.0.text.len() as u32
+    }
+}
+
+/// A child slot of a green node: either a nested node or a leaf token.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum GreenElement {
+    Node(GreenNode),
+    Token(GreenToken),
+}
+
+impl GreenElement {
+    pub fn kind(&self) -> SyntaxKind {
+        match self {
+            GreenElement::Node(node) => node.kind(),
+            GreenElement::Token(token) => token.kind(),
+        }
+    }
+
+    fn text_len(&self) -> u32 {
+        match self {
+            GreenElement::Node(node) => node.text_len(),
+            GreenElement::Token(token) => token.text_len(),
+        }
+    }
+
+    /// A child's contribution to its parent's precomputed hash. For a node
+    /// child this is just `node.0.hash` (already computed once, O(1) to
+    /// read); for a token it's hashed directly since tokens are leaves and
+    /// don't carry a cached hash of their own.
+    fn combined_hash(&self) -> u64 {
+        match self {
+            GreenElement::Node(node) => node.0.hash,
+            GreenElement::Token(token) => hash_of(&*token.0),
+        }
+    }
+}
+
+fn hash_of<T: Hash>(value: &T) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
+impl From<GreenNode> for GreenElement {
+    fn from(node: GreenNode) -> Self {
+        GreenElement::Node(node)
+    }
+}
+
+impl From<GreenToken> for GreenElement {
+    fn from(token: GreenToken) -> Self {
+        GreenElement::Token(token)
+    }
+}
+
+#[derive(Debug)]
+struct GreenNodeData {
+    kind: SyntaxKind,
+    text_len: u32,
+    children: Vec<GreenElement>,
+    /// Hash of `kind`, `text_len` and each child's own already-computed
+    /// hash (`GreenElement::combined_hash`), computed once at construction.
+    /// Reading it costs O(1); crucially, computing it never re-hashes a
+    /// child's own subtree, only the single number that subtree already
+    /// produced. Without this, `Hash`/`Eq` derived directly over `children`
+    /// would walk an entire subtree on every comparison, making interning
+    /// a chain of `n` nested nodes (e.g. an ordinary `a.b.c....nil` prefix
+    /// chain) cost O(n^2) instead of O(n).
+    hash: u64,
+}
+
+/// An interior node of the green tree: a kind, its total text length, and
+/// its children. Cheap to clone (it's an `Rc`) and carries no position.
+#[derive(Debug, Clone)]
+pub struct GreenNode(Rc<GreenNodeData>);
+
+impl GreenNode {
+    pub fn new(kind: SyntaxKind, children: Vec<GreenElement>) -> Self {
+        let text_len: u32 = children.iter().map(GreenElement::text_len).sum();
+
+        let mut hasher = DefaultHasher::new();
+        kind.hash(&mut hasher);
+        text_len.hash(&mut hasher);
+        for child in &children {
+            child.kind().hash(&mut hasher);
+            child.combined_hash().hash(&mut hasher);
+        }
+        let hash = hasher.finish();
+
+        GreenNode(Rc::new(GreenNodeData {
+            kind,
+            text_len,
+            children,
+            hash,
+        }))
+    }
+
+    pub fn kind(&self) -> SyntaxKind {
+        self.0.kind
+    }
+
+    pub fn text_len(&self) -> u32 {
+        self.0.text_len
+    }
+
+    pub fn children(&self) -> &[GreenElement] {
+        &self.0.children
+    }
+
+    /// True if `a` and `b` are the exact same interned allocation, not just
+    /// structurally equal. Mostly useful to confirm subtree sharing after
+    /// going through a [`NodeCache`].
+    pub fn ptr_eq(a: &GreenNode, b: &GreenNode) -> bool {
+        Rc::ptr_eq(&a.0, &b.0)
+    }
+}
+
+impl PartialEq for GreenNode {
+    /// Cheap in the common case: children that came from the same
+    /// [`NodeCache`] are the same `Rc` allocation, so each child only needs
+    /// a pointer comparison. Full structural comparison is only paid for
+    /// children that for some reason aren't already interned.
+    fn eq(&self, other: &Self) -> bool {
+        if Rc::ptr_eq(&self.0, &other.0) {
+            return true;
+        }
+        self.0.hash == other.0.hash
+            && self.0.kind == other.0.kind
+            && self.0.text_len == other.0.text_len
+            && self.0.children.len() == other.0.children.len()
+            && self
+                .0
+                .children
+                .iter()
+                .zip(other.0.children.iter())
+                .all(|(a, b)| a == b)
+    }
+}
+
+impl Eq for GreenNode {}
+
+impl Hash for GreenNode {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        state.write_u64(self.0.hash);
+    }
+}
+
+impl Drop for GreenNode {
+    /// A `GreenNode`'s children form an ordinary owned `Rc` chain, so an
+    /// unremarkable deep term (e.g. an `a.b.c....nil` prefix chain) would
+    /// otherwise blow the stack on drop: the compiler-generated glue frees
+    /// each level by recursing into the next. Instead, unlink this node's
+    /// children onto a worklist and free the tree one level at a time, the
+    /// same fix rowan itself applies to its own green tree.
+    fn drop(&mut self) {
+        let Some(data) = Rc::get_mut(&mut self.0) else {
+            // Still referenced elsewhere: the refcount decrement that
+            // happens after this returns is all that's needed here.
+            return;
+        };
+        let mut worklist = std::mem::take(&mut data.children);
+        while let Some(element) = worklist.pop() {
+            if let GreenElement::Node(mut node) = element {
+                if let Some(data) = Rc::get_mut(&mut node.0) {
+                    worklist.append(&mut data.children);
+                }
+                // `node` drops here with its children already unlinked (or
+                // it's shared, in which case this is just a refcount
+                // decrement) — either way this can't recurse further.
+            }
+        }
+    }
+}
+
+/// Interns green nodes so structurally-identical subtrees (repeated `nil`
+/// leaves, repeated process `Name` terms, ...) are allocated once and
+/// shared, the same trick rowan's own `NodeCache` uses. Tokens, such as
+/// the channel names inside a prefix, are not interned (see [`NodeCache::token`]).
+#[derive(Default)]
+pub struct NodeCache {
+    nodes: HashSet<GreenNode>,
+}
+
+impl NodeCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Build (or reuse) a green node for `kind` with the given children.
+    pub fn node(&mut self, kind: SyntaxKind, children: Vec<GreenElement>) -> GreenNode {
+        let candidate = GreenNode::new(kind, children);
+        if let Some(existing) = self.nodes.get(&candidate) {
+            existing.clone()
+        } else {
+            self.nodes.insert(candidate.clone());
+            candidate
+        }
+    }
+
+    /// Tokens hold their own text and aren't deduplicated: a `NAME` token's
+    /// text is rarely repeated, so interning it would just cost a lookup.
+    pub fn token(&self, kind: SyntaxKind, text: impl Into<Box<str>>) -> GreenToken {
+        GreenToken::new(kind, text)
+    }
+
+    /// Number of distinct green nodes interned so far.
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+}
+
+struct SyntaxNodeData {
+    parent: Option<SyntaxNode>,
+    green: GreenNode,
+    offset: u32,
+}
+
+/// A cursor into a specific position of a green tree ("red" node). Computes
+/// its absolute [`TextRange`] from its parent's offset plus the lengths of
+/// its preceding siblings, and holds a parent pointer for upward navigation.
+#[derive(Clone)]
+pub struct SyntaxNode(Rc<SyntaxNodeData>);
+
+impl std::fmt::Debug for SyntaxNode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SyntaxNode")
+            .field("kind", &self.kind())
+            .field("range", &self.text_range())
+            .finish()
+    }
+}
+
+impl PartialEq for SyntaxNode {
+    /// Structural equality over the underlying green tree; two cursors at
+    /// different offsets but with identical content compare equal, matching
+    /// how `Node` was compared before spans existed.
+    fn eq(&self, other: &Self) -> bool {
+        self.0.green == other.0.green
+    }
+}
+impl Eq for SyntaxNode {}
+
+impl std::hash::Hash for SyntaxNode {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.0.green.hash(state);
+    }
+}
+
+impl SyntaxNode {
+    /// Create a cursor at the root of a standalone green tree.
+    pub fn new_root(green: GreenNode) -> Self {
+        SyntaxNode(Rc::new(SyntaxNodeData {
+            parent: None,
+            green,
+            offset: 0,
+        }))
+    }
+
+    fn new_child(parent: SyntaxNode, green: GreenNode, offset: u32) -> Self {
+        SyntaxNode(Rc::new(SyntaxNodeData {
+            parent: Some(parent),
+            green,
+            offset,
+        }))
+    }
+
+    pub fn kind(&self) -> SyntaxKind {
+        self.0.green.kind()
+    }
+
+    pub fn green(&self) -> &GreenNode {
+        &self.0.green
+    }
+
+    pub fn text_range(&self) -> TextRange {
+        TextRange::new(self.0.offset, self.0.offset + self.0.green.text_len())
+    }
+
+    pub fn parent(&self) -> Option<SyntaxNode> {
+        self.0.parent.clone()
+    }
+
+    /// Direct children, nodes and tokens (including trivia), in source order.
+    pub fn children_with_tokens(&self) -> impl Iterator<Item = SyntaxElement> + '_ {
+        let mut offset = self.0.offset;
+        self.0.green.children().iter().map(move |element| {
+            let start = offset;
+            offset += element.text_len();
+            match element {
+                GreenElement::Node(green) => {
+                    SyntaxElement::Node(SyntaxNode::new_child(self.clone(), green.clone(), start))
+                }
+                GreenElement::Token(green) => SyntaxElement::Token(SyntaxToken {
+                    parent: self.clone(),
+                    green: green.clone(),
+                    offset: start,
+                }),
+            }
+        })
+    }
+
+    /// Direct child nodes (tokens filtered out).
+    pub fn children(&self) -> impl Iterator<Item = SyntaxNode> + '_ {
+        self.children_with_tokens().filter_map(|element| match element {
+            SyntaxElement::Node(node) => Some(node),
+            SyntaxElement::Token(_) => None,
+        })
+    }
+
+    /// Direct, non-trivia child tokens.
+    pub fn tokens(&self) -> impl Iterator<Item = SyntaxToken> + '_ {
+        self.children_with_tokens().filter_map(|element| match element {
+            SyntaxElement::Token(token) if !token.kind().is_trivia() => Some(token),
+            _ => None,
+        })
+    }
+
+    /// The exact original source text this node spans, trivia included.
+    pub fn text(&self) -> String {
+        let mut buf = String::new();
+        collect_text(&self.0.green, &mut buf);
+        buf
+    }
+}
+
+fn collect_text(green: &GreenNode, buf: &mut String) {
+    for child in green.children() {
+        match child {
+            GreenElement::Node(node) => collect_text(node, buf),
+            GreenElement::Token(token) => buf.push_str(token.text()),
+        }
+    }
+}
+
+/// A leaf cursor: a token plus the absolute position it was found at.
+#[derive(Clone)]
+pub struct SyntaxToken {
+    parent: SyntaxNode,
+    green: GreenToken,
+    offset: u32,
+}
+
+impl std::fmt::Debug for SyntaxToken {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SyntaxToken")
+            .field("kind", &self.kind())
+            .field("text", &self.text())
+            .field("range", &self.text_range())
+            .finish()
+    }
+}
+
+impl SyntaxToken {
+    pub fn kind(&self) -> SyntaxKind {
+        self.green.kind()
+    }
+
+    pub fn text(&self) -> &str {
+        self.green.text()
+    }
+
+    pub fn text_range(&self) -> TextRange {
+        TextRange::new(self.offset, self.offset + self.green.text_len())
+    }
+
+    pub fn parent(&self) -> &SyntaxNode {
+        &self.parent
+    }
+}
+
+/// Either half of [`SyntaxNode::children_with_tokens`].
+#[derive(Debug, Clone)]
+pub enum SyntaxElement {
+    Node(SyntaxNode),
+    Token(SyntaxToken),
+}
+
+impl SyntaxElement {
+    pub fn kind(&self) -> SyntaxKind {
+        match self {
+            SyntaxElement::Node(node) => node.kind(),
+            SyntaxElement::Token(token) => token.kind(),
+        }
+    }
+
+    pub fn text_range(&self) -> TextRange {
+        match self {
+            SyntaxElement::Node(node) => node.text_range(),
+            SyntaxElement::Token(token) => token.text_range(),
+        }
+    }
+}
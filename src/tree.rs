@@ -1,5 +1,7 @@
 use std::collections::BTreeMap;
 
+use crate::syntax::{GreenElement, GreenNode, NodeCache, SyntaxKind, SyntaxNode, TextRange};
+
 #[derive(Debug, Clone, Hash, PartialEq, Eq, PartialOrd, Ord)]
 pub enum Action {
     In(String),
@@ -54,23 +56,257 @@ impl Action {
             Out(_) => Out(new_channel.into()),
         }
     }
+
+    /// Read an action off the `IN`/`OUT` keyword token and the `NAME` token
+    /// that follow it among `syntax`'s direct children.
+    fn from_tokens(syntax: &SyntaxNode) -> Self {
+        let mut tokens = syntax.tokens();
+        let keyword = tokens
+            .find(|t| matches!(t.kind(), SyntaxKind::IN | SyntaxKind::OUT))
+            .expect("action node missing an IN/OUT keyword token");
+        let name = tokens
+            .find(|t| t.kind() == SyntaxKind::NAME)
+            .expect("action node missing its channel NAME token");
+        match keyword.kind() {
+            SyntaxKind::IN => Action::In(name.text().to_owned()),
+            SyntaxKind::OUT => Action::Out(name.text().to_owned()),
+            _ => unreachable!(),
+        }
+    }
+
+    /// The `IN`/`OUT` keyword and channel `NAME` tokens for this action.
+    fn to_green(&self, cache: &NodeCache) -> Vec<GreenElement> {
+        let (keyword, name) = match self {
+            Action::In(name) => (SyntaxKind::IN, name),
+            Action::Out(name) => (SyntaxKind::OUT, name),
+        };
+        let keyword_text = match keyword {
+            SyntaxKind::IN => "in",
+            SyntaxKind::OUT => "out",
+            _ => unreachable!(),
+        };
+        vec![
+            cache.token(keyword, keyword_text).into(),
+            cache.token(SyntaxKind::NAME, name.as_str()).into(),
+        ]
+    }
 }
 
 pub type Map = BTreeMap<String, String>;
 
-/// Nodes used in the syntax tree. The tree is generated by the parser: ['crate::parser::parse']
-#[derive(Debug, PartialEq, Eq, Hash, Clone)]
-pub enum Node {
-    Recurse(String, Box<Node>),
-    Restrict(Box<Node>, Action),
-    Relabel(Box<Node>, Map),
-    Compose(Box<Node>, Box<Node>),
-    Choice(Box<Node>, Box<Node>),
-    Prefix(Action, Box<Node>),
-    Name(String), //leaf
+/// Read a `Relabel` node's `old -> new` pairs back off its flat run of
+/// `NAME` tokens (old, new, old, new, ...).
+fn map_from_tokens(syntax: &SyntaxNode) -> Map {
+    let names: Vec<String> = syntax
+        .tokens()
+        .filter(|t| t.kind() == SyntaxKind::NAME)
+        .map(|t| t.text().to_owned())
+        .collect();
+    names
+        .chunks(2)
+        .map(|pair| (pair[0].clone(), pair[1].clone()))
+        .collect()
+}
+
+fn map_to_green(map: &Map, cache: &NodeCache) -> Vec<GreenElement> {
+    map.iter()
+        .flat_map(|(old, new)| {
+            [
+                cache.token(SyntaxKind::NAME, old.as_str()).into(),
+                cache.token(SyntaxKind::NAME, new.as_str()).into(),
+            ]
+        })
+        .collect()
+}
+
+/// Typed shape of a [`Node`], read off its underlying [`SyntaxNode`] on
+/// demand. Mirrors the variants the old lossy `Node` enum used to carry
+/// directly, so callers that matched on it before only need to match on
+/// `node.kind()` now.
+pub enum NodeKind {
+    Recurse(String, Node),
+    Restrict(Node, Action),
+    Relabel(Node, Map),
+    Compose(Node, Node),
+    Choice(Node, Node),
+    Prefix(Action, Node),
+    Name(String),
     Nil,
 }
 
+/// A term in the CCS syntax tree.
+///
+/// This is a thin, typed view over a [`SyntaxNode`] red cursor: unlike the
+/// old boxed `Node` enum it carries no data of its own, so every term knows
+/// its absolute source [`TextRange`] and round-trips back to the exact
+/// original text (whitespace and comments included) via [`Node::text`]. The
+/// tree itself is generated by the parser: ['crate::parser::parse']
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Node {
+    syntax: SyntaxNode,
+}
+
+impl Node {
+    /// Wrap a raw syntax cursor as a typed `Node`. Unwraps a `ROOT` wrapper
+    /// if present, so callers always see the actual term.
+    pub fn new(syntax: SyntaxNode) -> Self {
+        if syntax.kind() == SyntaxKind::ROOT {
+            let inner = syntax
+                .children()
+                .next()
+                .expect("ROOT node must wrap exactly one term");
+            return Node::new(inner);
+        }
+        Node { syntax }
+    }
+
+    pub fn syntax(&self) -> &SyntaxNode {
+        &self.syntax
+    }
+
+    /// The absolute byte range of this term in the original source.
+    pub fn text_range(&self) -> TextRange {
+        self.syntax.text_range()
+    }
+
+    /// The exact original source text this term spans, trivia included.
+    pub fn text(&self) -> String {
+        self.syntax.text()
+    }
+
+    pub fn kind(&self) -> NodeKind {
+        match self.syntax.kind() {
+            SyntaxKind::RECURSE => {
+                let name = self
+                    .syntax
+                    .tokens()
+                    .find(|t| t.kind() == SyntaxKind::NAME)
+                    .expect("Recurse node missing its bound NAME token")
+                    .text()
+                    .to_owned();
+                let body = Node::new(child(&self.syntax));
+                NodeKind::Recurse(name, body)
+            }
+            SyntaxKind::RESTRICT => {
+                let body = Node::new(child(&self.syntax));
+                let action = Action::from_tokens(&self.syntax);
+                NodeKind::Restrict(body, action)
+            }
+            SyntaxKind::RELABEL => {
+                let body = Node::new(child(&self.syntax));
+                let map = map_from_tokens(&self.syntax);
+                NodeKind::Relabel(body, map)
+            }
+            SyntaxKind::COMPOSE => {
+                let (a, b) = two_children(&self.syntax);
+                NodeKind::Compose(Node::new(a), Node::new(b))
+            }
+            SyntaxKind::CHOICE => {
+                let (a, b) = two_children(&self.syntax);
+                NodeKind::Choice(Node::new(a), Node::new(b))
+            }
+            SyntaxKind::PREFIX => {
+                let action = Action::from_tokens(&self.syntax);
+                let body = Node::new(child(&self.syntax));
+                NodeKind::Prefix(action, body)
+            }
+            SyntaxKind::NAME => {
+                let name = self
+                    .syntax
+                    .tokens()
+                    .next()
+                    .expect("Name node missing its NAME token")
+                    .text()
+                    .to_owned();
+                NodeKind::Name(name)
+            }
+            SyntaxKind::NIL => NodeKind::Nil,
+            other => unreachable!("{:?} is not a term node kind", other),
+        }
+    }
+
+    pub fn recurse(cache: &mut NodeCache, name: impl Into<String>, body: Node) -> Node {
+        let mut children = vec![cache.token(SyntaxKind::REC, "rec").into()];
+        children.push(cache.token(SyntaxKind::NAME, name.into()).into());
+        children.push(cache.token(SyntaxKind::DOT, ".").into());
+        children.push(GreenElement::Node(body.syntax.green().clone()));
+        Node::from_green(cache.node(SyntaxKind::RECURSE, children))
+    }
+
+    pub fn restrict(cache: &mut NodeCache, body: Node, action: Action) -> Node {
+        let mut children = vec![GreenElement::Node(body.syntax.green().clone())];
+        children.push(cache.token(SyntaxKind::BACKSLASH, "\\").into());
+        children.extend(action.to_green(cache));
+        Node::from_green(cache.node(SyntaxKind::RESTRICT, children))
+    }
+
+    pub fn relabel(cache: &mut NodeCache, body: Node, map: Map) -> Node {
+        let mut children = vec![GreenElement::Node(body.syntax.green().clone())];
+        children.push(cache.token(SyntaxKind::LBRACKET, "[").into());
+        children.extend(map_to_green(&map, cache));
+        children.push(cache.token(SyntaxKind::RBRACKET, "]").into());
+        Node::from_green(cache.node(SyntaxKind::RELABEL, children))
+    }
+
+    pub fn compose(cache: &mut NodeCache, a: Node, b: Node) -> Node {
+        let children = vec![
+            GreenElement::Node(a.syntax.green().clone()),
+            cache.token(SyntaxKind::PIPE, "|").into(),
+            GreenElement::Node(b.syntax.green().clone()),
+        ];
+        Node::from_green(cache.node(SyntaxKind::COMPOSE, children))
+    }
+
+    pub fn choice(cache: &mut NodeCache, a: Node, b: Node) -> Node {
+        let children = vec![
+            GreenElement::Node(a.syntax.green().clone()),
+            cache.token(SyntaxKind::PLUS, "+").into(),
+            GreenElement::Node(b.syntax.green().clone()),
+        ];
+        Node::from_green(cache.node(SyntaxKind::CHOICE, children))
+    }
+
+    pub fn prefix(cache: &mut NodeCache, action: Action, body: Node) -> Node {
+        let mut children = action.to_green(cache);
+        children.push(cache.token(SyntaxKind::DOT, ".").into());
+        children.push(GreenElement::Node(body.syntax.green().clone()));
+        Node::from_green(cache.node(SyntaxKind::PREFIX, children))
+    }
+
+    pub fn name(cache: &mut NodeCache, name: impl Into<String>) -> Node {
+        let children = vec![cache.token(SyntaxKind::NAME, name.into()).into()];
+        Node::from_green(cache.node(SyntaxKind::NAME, children))
+    }
+
+    pub fn nil(cache: &mut NodeCache) -> Node {
+        let children = vec![cache.token(SyntaxKind::NIL, "nil").into()];
+        Node::from_green(cache.node(SyntaxKind::NIL, children))
+    }
+
+    fn from_green(green: GreenNode) -> Node {
+        Node {
+            syntax: SyntaxNode::new_root(green),
+        }
+    }
+}
+
+/// The single child node of a unary composite (`Recurse`, `Restrict`,
+/// `Relabel`, `Prefix`): either a nested composite or a `NAME`/`NIL` leaf.
+fn child(syntax: &SyntaxNode) -> SyntaxNode {
+    syntax
+        .children()
+        .next()
+        .expect("composite node missing its child term")
+}
+
+/// The two child nodes of a binary composite (`Compose`, `Choice`).
+fn two_children(syntax: &SyntaxNode) -> (SyntaxNode, SyntaxNode) {
+    let mut children = syntax.children();
+    let a = children.next().expect("binary node missing its left child");
+    let b = children.next().expect("binary node missing its right child");
+    (a, b)
+}
+
 impl std::fmt::Display for Node {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{}", maxfmt(0, self))
@@ -78,20 +314,20 @@ impl std::fmt::Display for Node {
 }
 
 fn infix_recurse(node: &Node, s: &mut String) {
-    use Node::*;
+    use NodeKind::*;
 
-    match node {
+    match node.kind() {
         Recurse(string, node) => {
             s.push_str(&format!("_rec {}.", string));
-            infix_recurse(node, s);
+            infix_recurse(&node, s);
         }
         Restrict(node, action) => {
-            infix_recurse(node, s);
+            infix_recurse(&node, s);
             s.push_str(&format!("\\{}", action));
         }
         Relabel(node, map) => {
-            infix_recurse(node, s);
-            s.push_str(&format!("[{}]", print_map(map)));
+            infix_recurse(&node, s);
+            s.push_str(&format!("[{}]", print_map(&map)));
         }
         Compose(node_a, node_b) => {
             s.push('(');
@@ -109,9 +345,9 @@ fn infix_recurse(node: &Node, s: &mut String) {
         }
         Prefix(action, node) => {
             s.push_str(&format!("{}.", action));
-            infix_recurse(node, s);
+            infix_recurse(&node, s);
         }
-        Name(string) => s.push_str(string),
+        Name(string) => s.push_str(&string),
         Nil => s.push_str("nil"),
     };
 }
@@ -127,13 +363,13 @@ impl Node {
 }
 
 fn maxfmt(indent: u8, node: &Node) -> String {
-    use Node::*;
+    use NodeKind::*;
     const TAB: &str = "   ";
     let tabs = TAB.repeat(indent as usize);
     let mut s = String::new();
-    match node {
+    match node.kind() {
         Recurse(string, node) => {
-            let node = maxfmt(indent + 1, node);
+            let node = maxfmt(indent + 1, &node);
             s.push_str(&format!(
                 "{tabs}Recurse(\n{tabs}{tab}{},\n{}\n{tabs})",
                 string,
@@ -143,7 +379,7 @@ fn maxfmt(indent: u8, node: &Node) -> String {
             ));
         }
         Restrict(node, action) => {
-            let node = maxfmt(indent + 1, node);
+            let node = maxfmt(indent + 1, &node);
             s.push_str(&format!(
                 "{tabs}Restrict(\n{tabs}{tab}{},\n{}\n{tabs})",
                 action,
@@ -153,18 +389,18 @@ fn maxfmt(indent: u8, node: &Node) -> String {
             ));
         }
         Relabel(node, map) => {
-            let node = maxfmt(indent + 1, node);
+            let node = maxfmt(indent + 1, &node);
             s.push_str(&format!(
                 "{tabs}Relabel(\n{tabs}{tab}{},\n{}\n{tabs})",
-                print_map(map),
+                print_map(&map),
                 node,
                 tab = TAB,
                 tabs = tabs
             ));
         }
         Compose(node_a, node_b) => {
-            let node_a = maxfmt(indent + 1, node_a);
-            let node_b = maxfmt(indent + 1, node_b);
+            let node_a = maxfmt(indent + 1, &node_a);
+            let node_b = maxfmt(indent + 1, &node_b);
             s.push_str(&format!(
                 "{tabs}Compose(\n{},\n{}\n{tabs})",
                 node_a,
@@ -173,8 +409,8 @@ fn maxfmt(indent: u8, node: &Node) -> String {
             ));
         }
         Choice(node_a, node_b) => {
-            let node_a = maxfmt(indent + 1, node_a);
-            let node_b = maxfmt(indent + 1, node_b);
+            let node_a = maxfmt(indent + 1, &node_a);
+            let node_b = maxfmt(indent + 1, &node_b);
             s.push_str(&format!(
                 "{tabs}Choice(\n{},\n{}\n{tabs})",
                 node_a,
@@ -183,7 +419,7 @@ fn maxfmt(indent: u8, node: &Node) -> String {
             ));
         }
         Prefix(action, node) => {
-            let node = maxfmt(indent + 1, node);
+            let node = maxfmt(indent + 1, &node);
             s.push_str(&format!(
                 "{tabs}Prefix(\n{tabs}{tab}{},\n{}\n{tabs})",
                 action,
@@ -209,3 +445,83 @@ fn print_map(map: &Map) -> String {
     }
     s
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prefix_chain_infixes_and_round_trips() {
+        let mut cache = NodeCache::new();
+        let nil = Node::nil(&mut cache);
+        let inner = Node::prefix(&mut cache, Action::Out("b".into()), nil);
+        let term = Node::prefix(&mut cache, Action::In("a".into()), inner);
+
+        assert_eq!(term.infix(), "In{a}.Out{b}.nil");
+        assert_eq!(term.text(), "ina.outb.nil");
+        assert_eq!(term.text_range(), TextRange::new(0, term.text().len() as u32));
+    }
+
+    #[test]
+    fn spans_are_absolute_for_nested_terms() {
+        let mut cache = NodeCache::new();
+        let nil = Node::nil(&mut cache);
+        let inner = Node::prefix(&mut cache, Action::Out("b".into()), nil);
+        let term = Node::prefix(&mut cache, Action::In("a".into()), inner);
+
+        let body = match term.kind() {
+            NodeKind::Prefix(_, body) => body,
+            _ => panic!("expected Prefix"),
+        };
+        // "in" + "a" + "." precede the nested Prefix term in the source.
+        assert_eq!(body.text_range().start(), 4);
+        assert_eq!(body.text(), "outb.nil");
+    }
+
+    #[test]
+    fn structurally_identical_subtrees_are_interned() {
+        let mut cache = NodeCache::new();
+        let nil_a = Node::nil(&mut cache);
+        let nil_b = Node::nil(&mut cache);
+
+        assert!(GreenNode::ptr_eq(nil_a.syntax().green(), nil_b.syntax().green()));
+        assert_eq!(cache.len(), 1);
+
+        let _ = Node::name(&mut cache, "x");
+        assert_eq!(cache.len(), 2);
+    }
+
+    #[test]
+    fn relabel_round_trips_its_map() {
+        let mut cache = NodeCache::new();
+        let name = Node::name(&mut cache, "p");
+        let mut map = Map::new();
+        map.insert("old".into(), "new".into());
+        let term = Node::relabel(&mut cache, name, map.clone());
+
+        match term.kind() {
+            NodeKind::Relabel(body, roundtripped) => {
+                match body.kind() {
+                    NodeKind::Name(name) => assert_eq!(name, "p"),
+                    _ => panic!("expected Name"),
+                }
+                assert_eq!(roundtripped, map);
+            }
+            _ => panic!("expected Relabel"),
+        }
+    }
+
+    #[test]
+    fn dropping_a_deep_chain_does_not_overflow_the_stack() {
+        let mut cache = NodeCache::new();
+        let mut node = Node::nil(&mut cache);
+        for i in 0..100_000 {
+            node = Node::prefix(&mut cache, Action::In(format!("c{}", i % 5)), node);
+        }
+
+        // The regression this guards against is a stack overflow on drop,
+        // not a wrong value, so simply reaching this line is the assertion.
+        drop(node);
+        drop(cache);
+    }
+}